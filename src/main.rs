@@ -1,16 +1,199 @@
 extern crate clap;
 extern crate console;
+extern crate crossterm;
+extern crate encoding_rs;
+extern crate encoding_rs_io;
+#[cfg(feature = "pcre2")]
+extern crate pcre2;
 extern crate regex;
 
 use clap::{App, Arg};
-use regex::Regex;
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use regex::{Regex, RegexSet};
 use std::fs::File;
 use std::io::Write;
+use std::sync::mpsc;
+use std::time::Duration;
 
 /// Options collected from the command line
 struct Options {
     restart_on_find: bool,
-    regexes: Vec<Regex>,
+    patterns: Vec<Pattern>,
+    /// Per-pattern content template, positional-aligned with `patterns`
+    content_templates: Vec<Option<String>>,
+    /// Per-pattern header template, positional-aligned with `patterns`
+    header_templates: Vec<Option<String>>,
+    use_color: bool,
+    use_pcre2: bool,
+    page_mode: bool,
+}
+
+/// Where `fa` should read its input from, resolved once in `main` and handed
+/// off to the background reader thread `search_and_display` spawns.
+enum InputSource {
+    File(String),
+    Stdin,
+}
+
+/// The matching backend behind a compiled `Pattern`.
+enum Engine {
+    /// The default backend: `regex`'s linear-time automaton.
+    Std(Regex),
+    /// The opt-in backend, which supports look-around and backreferences at
+    /// the cost of the matching guarantees `regex` provides.
+    #[cfg(feature = "pcre2")]
+    Pcre2(pcre2::bytes::Regex),
+}
+
+/// A single compiled pattern, together with the source text it was built
+/// from so headers and diagnostics can show the original pattern regardless
+/// of which backend compiled it (the two engines have different `Debug`
+/// representations).
+struct Pattern {
+    text: String,
+    engine: Engine,
+}
+
+impl Pattern {
+    /// Compile `text` with the `regex` engine, or with PCRE2 (JIT enabled)
+    /// when `use_pcre2` is set.
+    fn compile(text: &str, use_pcre2: bool) -> Result<Pattern, String> {
+        let engine = build_engine(text, use_pcre2)?;
+        Ok(Pattern { text: text.to_string(), engine })
+    }
+
+    /// Does this pattern match anywhere in `line`?
+    fn is_match(&self, line: &str) -> bool {
+        match &self.engine {
+            Engine::Std(r) => r.is_match(line),
+            #[cfg(feature = "pcre2")]
+            Engine::Pcre2(r) => r.is_match(line.as_bytes()).unwrap_or(false),
+        }
+    }
+
+    /// Byte ranges of every match of this pattern in `line`.
+    fn find_iter(&self, line: &str) -> Vec<(usize, usize)> {
+        match &self.engine {
+            Engine::Std(r) => r.find_iter(line).map(|m| (m.start(), m.end())).collect(),
+            #[cfg(feature = "pcre2")]
+            Engine::Pcre2(r) => r
+                .find_iter(line.as_bytes())
+                .filter_map(|m| m.ok())
+                .map(|m| (m.start(), m.end()))
+                .collect(),
+        }
+    }
+
+    /// Expand `$1`, `${name}`, `$0`, and `$$` references in `template` using this
+    /// pattern's captures from matching `line`. Returns `None` if the pattern
+    /// doesn't match `line`, or if a referenced group didn't participate in the
+    /// match, so the caller can fall back to the raw line.
+    fn expand(&self, line: &str, template: &str) -> Option<String> {
+        match &self.engine {
+            Engine::Std(r) => {
+                let caps = r.captures(line)?;
+                expand_template(template, |key| {
+                    if let Ok(index) = key.parse::<usize>() {
+                        caps.get(index).map(|m| m.as_str().to_string())
+                    } else {
+                        caps.name(key).map(|m| m.as_str().to_string())
+                    }
+                })
+            }
+            #[cfg(feature = "pcre2")]
+            Engine::Pcre2(r) => {
+                let caps = r.captures(line.as_bytes()).ok()??;
+                expand_template(template, |key| {
+                    let m = if let Ok(index) = key.parse::<usize>() {
+                        caps.get(index)
+                    } else {
+                        caps.name(key)
+                    };
+                    m.and_then(|m| std::str::from_utf8(m.as_bytes()).ok())
+                        .map(|s| s.to_string())
+                })
+            }
+        }
+    }
+}
+
+/// Expand `$1`, `${name}`, `$0`, and `$$` references in `template`, resolving each
+/// referenced group through `lookup`. Mirrors `regex::Captures::expand`'s syntax so
+/// the same template strings work regardless of which engine matched. Returns
+/// `None` if `lookup` returns `None` for any referenced group (i.e. it didn't
+/// participate in the match).
+fn expand_template(template: &str, lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('$') => {
+                chars.next();
+                out.push('$');
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '}' {
+                        chars.next();
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                out.push_str(&lookup(&name)?);
+            }
+            Some(&c) if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' {
+                        name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                out.push_str(&lookup(&name)?);
+            }
+            _ => out.push('$'),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(feature = "pcre2")]
+fn build_engine(text: &str, use_pcre2: bool) -> Result<Engine, String> {
+    if use_pcre2 {
+        pcre2::bytes::RegexBuilder::new()
+            .jit_if_available(true)
+            // Match ranges are reported in bytes; UTF mode guarantees those
+            // bytes always fall on char boundaries for the valid UTF-8 lines
+            // fa reads, the same guarantee the `regex` backend gives for free.
+            .utf(true)
+            .build(text)
+            .map(Engine::Pcre2)
+            .map_err(|e| e.to_string())
+    } else {
+        Regex::new(text).map(Engine::Std).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(feature = "pcre2"))]
+fn build_engine(text: &str, use_pcre2: bool) -> Result<Engine, String> {
+    if use_pcre2 {
+        Err(String::from(
+            "fa was built without PCRE2 support; rebuild with --features pcre2",
+        ))
+    } else {
+        Regex::new(text).map(Engine::Std).map_err(|e| e.to_string())
+    }
 }
 
 /// Validates u16 command line values
@@ -22,16 +205,101 @@ fn u16_validator(s: String) -> Result<(), String> {
     }
 }
 
-/// Validates regex command line values
+/// Validates regex command line values against whichever matching engine is selected
 #[allow(dead_code)]
-fn regex_validator(s: String) -> Result<(), String> {
-    match Regex::new(&s) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(String::from("Invalid regular expression")),
+fn regex_validator(s: String, use_pcre2: bool) -> Result<(), String> {
+    build_engine(&s, use_pcre2).map(|_| ())
+}
+
+/// Validates --encoding command line values
+#[allow(dead_code)]
+fn encoding_validator(s: String) -> Result<(), String> {
+    if s == "auto" || Encoding::for_label(s.as_bytes()).is_some() {
+        Ok(())
+    } else {
+        Err(String::from("Unrecognized encoding label"))
+    }
+}
+
+/// Wrap `reader` in a transcoding layer that sniffs a leading BOM to detect
+/// UTF-16LE/BE/UTF-8 and otherwise decodes as `encoding_label` (a WHATWG label,
+/// or "auto" to assume UTF-8 when no BOM is present), always emitting UTF-8
+/// downstream so `read_line` never fails on non-UTF-8 input.
+fn decode_input<R: std::io::Read>(reader: R, encoding_label: &str) -> std::io::BufReader<encoding_rs_io::DecodeReaderBytes<R, Vec<u8>>> {
+    let fallback = if encoding_label == "auto" {
+        Some(encoding_rs::UTF_8)
+    } else {
+        Encoding::for_label(encoding_label.as_bytes())
+    };
+
+    let transcoded = DecodeReaderBytesBuilder::new()
+        .encoding(fallback)
+        .bom_override(true)
+        .build(reader);
+
+    std::io::BufReader::new(transcoded)
+}
+
+/// Reads lines from `reader` and sends each one over `tx`, stopping at EOF, a
+/// read error, or once the receiving end has gone away.
+fn read_loop<T: std::io::BufRead>(mut reader: T, tx: &mpsc::Sender<Option<String>>) {
+    loop {
+        let mut l = String::new();
+        match reader.read_line(&mut l) {
+            Ok(0) => break,
+            Ok(_) => {
+                if tx.send(Some(l)).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Spawn a background thread that opens `source`, decodes it per `encoding_label`,
+/// and streams its lines back over a channel. Reading lives on its own thread so the
+/// main loop stays responsive to resize and key events even while a read blocks.
+fn spawn_reader(source: InputSource, encoding_label: String) -> mpsc::Receiver<Option<String>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        match source {
+            InputSource::File(path) => match File::open(&path) {
+                Ok(f) => read_loop(decode_input(f, &encoding_label), &tx),
+                Err(_) => eprintln!("Unable to open {}", path),
+            },
+            InputSource::Stdin => {
+                let stdin = std::io::stdin();
+                read_loop(decode_input(stdin.lock(), &encoding_label), &tx);
+            }
+        }
+        let _ = tx.send(None);
+    });
+    rx
+}
+
+/// Reads a positional-aligned, multi-valued argument such as `--template`: one
+/// value per `REGEX`, in the same order. Returns `None` for every position when
+/// the argument was omitted entirely, since it's optional per-space.
+fn aligned_values(matches: &clap::ArgMatches, name: &str, count: usize) -> Vec<Option<String>> {
+    match matches.values_of(name) {
+        Some(values) => {
+            let mut v: Vec<Option<String>> = values.map(|s| Some(s.to_string())).collect();
+            v.resize(count, None);
+            v
+        }
+        None => vec![None; count],
     }
 }
 
 fn main() {
+    // --pcre2 has to be known before REGEX is validated, so pre-scan the raw
+    // arguments for it rather than reading it back out of `matches` later.
+    let use_pcre2 = std::env::args().any(|a| a == "--pcre2");
+
     // Parse the command line using clap
     let matches = App::new("fa")
         .version("0.2.0")
@@ -40,7 +308,7 @@ fn main() {
         .arg(Arg::with_name("REGEX")
              .help("Regular expression to find in the input")
              .required(true)
-             .validator(regex_validator)
+             .validator(move |s| regex_validator(s, use_pcre2))
              .multiple(true)
              .index(1))
         .arg(Arg::with_name("INPUT")
@@ -52,36 +320,84 @@ fn main() {
              .help("Restart display each time REGEX is found again, without waiting for the screen to fill")
              .long("restart_on_find")
              .short("r"))
+        .arg(Arg::with_name("color")
+             .help("Highlight matched text: auto, always, or never")
+             .long("color")
+             .takes_value(true)
+             .possible_values(&["auto", "always", "never"])
+             .default_value("auto"))
+        .arg(Arg::with_name("encoding")
+             .help("Source text encoding (a WHATWG label, e.g. utf-8, utf-16le, windows-1252). \
+                    auto sniffs a BOM and assumes UTF-8 otherwise")
+             .long("encoding")
+             .takes_value(true)
+             .validator(encoding_validator)
+             .default_value("auto"))
+        .arg(Arg::with_name("pcre2")
+             .help("Match using PCRE2 instead of the default regex engine, enabling \
+                    look-around and backreferences (requires the `pcre2` build feature)")
+             .long("pcre2"))
+        .arg(Arg::with_name("template")
+             .help("Print this capture-expanded template (e.g. \"${txid}\") instead of \
+                    the matching line, one per REGEX in order; omit for a REGEX to print \
+                    the raw line")
+             .long("template")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("header")
+             .help("Capture-expanded template for this space's header (e.g. \"Transaction \
+                    ${txid}\"), one per REGEX in order; omit for a REGEX to label it with \
+                    the pattern text")
+             .long("header")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1))
+        .arg(Arg::with_name("page")
+             .help("Pause a space once it fills, waiting for space/enter to continue or q \
+                    to quit, instead of silently overwriting it")
+             .long("page"))
        .get_matches();
 
     // Unwrapping is appropriate here because REGEX is a required
     // argument and we shouldn't get here if it's not present.
-    let regexes: Vec<Regex> = matches
+    let patterns: Vec<Pattern> = matches
         .values_of("REGEX")
         .unwrap()
-        .map(|s| Regex::new(s).unwrap())
+        .map(|s| Pattern::compile(s, use_pcre2).unwrap())
         .collect();
 
+    let content_templates = aligned_values(&matches, "template", patterns.len());
+    let header_templates = aligned_values(&matches, "header", patterns.len());
+
     let restart_on_find = matches.is_present("restart_on_find");
 
+    let use_color = match matches.value_of("color").unwrap() {
+        "always" => true,
+        "never" => false,
+        _ => console::Term::stdout().is_term(),
+    };
+
+    let page_mode = matches.is_present("page");
+
     let opt = Options {
         restart_on_find: restart_on_find,
-        regexes: regexes,
+        patterns: patterns,
+        content_templates: content_templates,
+        header_templates: header_templates,
+        use_color: use_color,
+        use_pcre2: use_pcre2,
+        page_mode: page_mode,
     };
 
-    match matches.value_of("INPUT") {
-        Some(filename) => {
-            if let Ok(f) = File::open(filename) {
-                let mut reader = std::io::BufReader::new(f);
-                search_and_display(&mut reader, opt);
-            } else {
-                eprintln!("Unable to open {}", filename);
-            }
-        }
-        _ => {
-            search_and_display(&mut std::io::stdin().lock(), opt);
-        }
-    }
+    let encoding_label = matches.value_of("encoding").unwrap().to_string();
+
+    let source = match matches.value_of("INPUT") {
+        Some(filename) => InputSource::File(filename.to_string()),
+        _ => InputSource::Stdin,
+    };
+
+    search_and_display(source, encoding_label, opt);
 }
 
 /// State of each display space.
@@ -94,18 +410,25 @@ enum State {
 }
 
 /// Data for each display space
-#[derive(Debug)]
 struct Space {
     /// Starting row of this display space
     start: i32,
     /// number of rows in this space
     rows: i32,
-    /// regex which when matched will cause a switch to this space
-    regex: Regex,
+    /// pattern which when matched will cause a switch to this space
+    pattern: Pattern,
     /// used to avoid re-starting in this space unless directed
     state: State,
     /// Header string to be printed at the top of the space
     header: String,
+    /// The label currently wrapped in `header`'s border, kept around so the
+    /// border can be rebuilt at the right width when the terminal resizes
+    label: String,
+    /// Template for this space's header; when set, re-expanded against the
+    /// captures of the line that triggers a switch into this space
+    header_template: Option<String>,
+    /// Template for what's printed in place of the raw matching line
+    content_template: Option<String>,
 }
 
 impl Space {
@@ -116,91 +439,309 @@ impl Space {
     }
 }
 
+/// Build a bordered header line of the form `--- [ label ] ---`, padded or
+/// truncated to fit within `cols` columns.
+fn build_header(label: &str, cols: u16) -> String {
+    let header_text = format!("[ {} ]", label);
+    use std::iter::repeat;
+    repeat('-').take(3).chain(header_text.chars())
+        .chain(repeat('-')).take((cols-1) as usize).collect::<String>() + "\n"
+}
+
+/// Style applied to the portion of a printed line that matched its space's regex
+fn highlight_style() -> console::Style {
+    console::Style::new().bold().yellow()
+}
+
+/// Build the text to print for line `l` in a space governed by `pattern`, highlighting
+/// matched substrings and truncating to fit within `cols` columns.
+///
+/// Truncation and highlighting are both computed in terms of `char` offsets (not
+/// bytes) so a multi-byte character is never split, and highlighted segments are
+/// always followed by a style reset even when a match is cut off by truncation.
+fn render_line(pattern: &Pattern, l: &str, cols: u16) -> String {
+    let style = highlight_style();
+    // Normalize away any line terminator rather than relying on the caller's
+    // string having (or not having) one: raw lines from `read_line` carry a
+    // trailing "\n", but expanded `--template` content doesn't.
+    let trimmed = l.trim_end_matches(|c| c == '\n' || c == '\r');
+    let chars: Vec<char> = trimmed.chars().collect();
+    let truncate = chars.len() >= cols as usize;
+    let limit = if truncate { (cols - 1) as usize } else { chars.len() };
+
+    let ranges: Vec<(usize, usize)> = pattern
+        .find_iter(trimmed)
+        .into_iter()
+        .map(|(start, end)| (trimmed[..start].chars().count(), trimmed[..end].chars().count()))
+        .filter(|&(start, _)| start < limit)
+        .map(|(start, end)| (start, end.min(limit)))
+        .collect();
+
+    let mut out = String::new();
+    let mut pos = 0;
+    for (start, end) in ranges {
+        if start > pos {
+            out.push_str(&chars[pos..start].iter().collect::<String>());
+        }
+        if end > start {
+            let segment: String = chars[start..end].iter().collect();
+            out.push_str(&style.apply_to(segment).to_string());
+        }
+        pos = pos.max(end);
+    }
+    if pos < limit {
+        out.push_str(&chars[pos..limit].iter().collect::<String>());
+    }
+    out.push('\n');
+    out
+}
+
 /// Do the main work of reading the input and writing to the display
-fn search_and_display<T: std::io::BufRead>(input: &mut T, mut opt: Options) {
+fn search_and_display(source: InputSource, encoding_label: String, mut opt: Options) {
+    console::set_colors_enabled(opt.use_color);
+
     let mut term = console::Term::stdout();
-    let (rows, cols) = term.size();
+    let (mut rows, mut cols) = term.size();
 
     term.clear_screen().unwrap();
 
-    let rows_to_use = rows - 1;
+    // When using the default `regex` engine, build a RegexSet up front so
+    // each line is scanned once, regardless of how many patterns the user
+    // supplied, instead of re-scanning the line once per `Space`. PCRE2 has
+    // no equivalent combined automaton, so that path falls back to checking
+    // each `Pattern` individually.
+    let set = if opt.use_pcre2 {
+        None
+    } else {
+        Some(
+            RegexSet::new(opt.patterns.iter().map(|p| p.text.as_str()))
+                .expect("patterns were already validated by regex_validator"),
+        )
+    };
 
-    // Divide the available space up if there's more than one regex
-    let mut display_spaces: Vec<Space> = Vec::new();
-    let lines_per_space = rows_to_use / (opt.regexes.len() as u16);
-    let mut next_line = 0;
-    for r in opt.regexes.drain(..) {
-        let header_text = format!("[ {:?} ]", &r);
-        use std::iter::repeat;
-        let full_header = repeat('-').take(3).chain(header_text.chars())
-            .chain(repeat('-')).take((cols-1) as usize).collect::<String>() + "\n";
-
-        display_spaces.push(Space {
-            start: next_line,
-            rows: (lines_per_space as i32),
-            state: State::Finding,
-            regex: r,
-            header: full_header,
-        });
-        next_line += lines_per_space as i32;
-    }
+    // Build one `Space` per pattern; `lay_out` below fills in start/rows/header.
+    let content_templates = std::mem::take(&mut opt.content_templates).into_iter();
+    let header_templates = std::mem::take(&mut opt.header_templates).into_iter();
+    let mut display_spaces: Vec<Space> = opt
+        .patterns
+        .drain(..)
+        .zip(content_templates)
+        .zip(header_templates)
+        .map(|((p, content_template), header_template)| {
+            // The original pattern text is used as the default label, rather than
+            // the engine's own Debug output, since `regex::Regex` and
+            // `pcre2::bytes::Regex` render differently. A `--header` template is
+            // only meaningful once a match has supplied captures to expand it
+            // against, so the pre-match label is always the pattern text.
+            let label = p.text.clone();
+            Space {
+                start: 0,
+                rows: 0,
+                state: State::Finding,
+                pattern: p,
+                header: String::new(),
+                label: label,
+                header_template: header_template,
+                content_template: content_template,
+            }
+        })
+        .collect();
 
-    // Draw headers, to separate spaces
-    for s in display_spaces.iter() {
-        s.move_to(&mut term);
-        term.write(s.header.as_bytes()).unwrap();
-    }
+    lay_out(&mut display_spaces, rows, cols);
+    draw_headers(&mut term, &display_spaces);
+
+    let rx = spawn_reader(source, encoding_label);
 
     let mut lines_printed_this_space = 0;
+    let mut paused: Option<usize> = None;
 
-    loop {
-        let mut changed_space = false;
-        let mut l = String::new();
-        match input.read_line(&mut l) {
-            Ok(n) => {
-                if n == 0 {
-                    // This indicates EOF
-                    break;
-                } else {
-                    // Got a line.
-                    for s in display_spaces.iter_mut() {
-
-                        // If we've changed spaces this loop (to some other space, presumably)
-                        // then we're not printing in this space anymore
-                        if changed_space {
-                            s.state = State::Finding;
+    if opt.page_mode {
+        crossterm::terminal::enable_raw_mode().ok();
+    }
+
+    'main: loop {
+        // Drain whatever terminal events (resizes, keypresses) have queued up
+        // without blocking, so the display stays responsive even while the
+        // reader thread is blocked on (or streaming) input.
+        while crossterm::event::poll(Duration::from_millis(0)).unwrap_or(false) {
+            match crossterm::event::read() {
+                Ok(Event::Resize(new_cols, new_rows)) => {
+                    cols = new_cols;
+                    rows = new_rows;
+                    lay_out(&mut display_spaces, rows, cols);
+                    term.clear_screen().unwrap();
+                    draw_headers(&mut term, &display_spaces);
+                }
+                Ok(Event::Key(key)) if opt.page_mode => {
+                    // raw mode disables ISIG, so Ctrl-C no longer raises SIGINT and
+                    // arrives here instead; q/Esc/Ctrl-C must always quit, not just
+                    // while paused, or a slow/rare-matching stream traps the user.
+                    let is_quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+                    if is_quit {
+                        break 'main;
+                    }
+
+                    if let Some(i) = paused {
+                        if let KeyCode::Char(' ') | KeyCode::Enter = key.code {
+                            display_spaces[i].state = State::Finding;
+                            paused = None;
                         }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if paused.is_some() {
+            std::thread::sleep(Duration::from_millis(20));
+            continue;
+        }
 
-                        if (s.state == State::Finding || opt.restart_on_find) && s.regex.is_match(&l) {
-                            // Swapping to a new space.
-                            s.move_to(&mut term);
-                            s.state = State::Printing;
-                            changed_space = true;
-                            term.write(s.header.as_bytes()).unwrap();
-                            lines_printed_this_space = 1;
+        match rx.try_recv() {
+            Ok(None) => break,
+            Err(mpsc::TryRecvError::Disconnected) => break,
+            Err(mpsc::TryRecvError::Empty) => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok(Some(l)) => {
+                let mut changed_space = false;
+
+                // Scan the line once with the combined RegexSet rather than
+                // re-running is_match for every space, when that's available.
+                let matched: std::collections::HashSet<usize> = match &set {
+                    Some(set) => set.matches(&l).into_iter().collect(),
+                    None => display_spaces
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, s)| s.pattern.is_match(&l))
+                        .map(|(i, _)| i)
+                        .collect(),
+                };
+
+                for (i, s) in display_spaces.iter_mut().enumerate() {
+
+                    // If we've changed spaces this loop (to some other space, presumably)
+                    // then we're not printing in this space anymore
+                    if changed_space {
+                        s.state = State::Finding;
+                    }
+
+                    if (s.state == State::Finding || opt.restart_on_find) && matched.contains(&i) {
+                        // Swapping to a new space. Label it with a value from the
+                        // triggering line when a header template was given.
+                        if let Some(template) = &s.header_template {
+                            if let Some(label) = s.pattern.expand(&l, template) {
+                                s.label = label;
+                            }
                         }
+                        s.header = build_header(&s.label, cols);
+                        s.move_to(&mut term);
+                        s.state = State::Printing;
+                        changed_space = true;
+                        term.write(s.header.as_bytes()).unwrap();
+                        lines_printed_this_space = 1;
+                    }
 
-                        if s.state == State::Printing {
-                            term.clear_line().unwrap();
-                            let print_string: String = if l.chars().count() >= cols as usize {
-                                l.chars().take((cols - 1) as usize).collect::<String>() + "\n"
-                            } else {
-                                l.clone()
-                            };
-                            term.write(print_string.as_bytes()).unwrap();
-                            lines_printed_this_space += 1;
+                    if s.state == State::Printing {
+                        term.clear_line().unwrap();
+                        let content = match &s.content_template {
+                            Some(template) => s.pattern.expand(&l, template).unwrap_or_else(|| l.clone()),
+                            None => l.clone(),
+                        };
+                        let print_string = render_line(&s.pattern, &content, cols);
+                        term.write(print_string.as_bytes()).unwrap();
+                        lines_printed_this_space += 1;
 
-                            // Have we reached the end of this space?
-                            if lines_printed_this_space >= s.rows {
+                        // Have we reached the end of this space?
+                        if lines_printed_this_space >= s.rows {
+                            if opt.page_mode {
+                                print_page_prompt(&mut term);
+                                paused = Some(i);
+                            } else {
                                 s.state = State::Finding;
                             }
                         }
                     }
                 }
             }
-            Err(e) => {
-                eprintln!("Error: {:?}", e);
-            }
         }
     }
+
+    if opt.page_mode {
+        crossterm::terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Recompute each space's starting row and height for the current terminal
+/// size, and rebuild its header border to the new width. Called once up front
+/// and again every time a resize event arrives.
+fn lay_out(display_spaces: &mut Vec<Space>, rows: u16, cols: u16) {
+    let rows_to_use = rows - 1;
+    let lines_per_space = rows_to_use / (display_spaces.len() as u16);
+    let mut next_line = 0;
+    for s in display_spaces.iter_mut() {
+        s.start = next_line;
+        s.rows = lines_per_space as i32;
+        s.header = build_header(&s.label, cols);
+        next_line += lines_per_space as i32;
+    }
+}
+
+/// Draw every space's header, to separate spaces on screen.
+fn draw_headers(term: &mut console::Term, display_spaces: &[Space]) {
+    for s in display_spaces.iter() {
+        s.move_to(term);
+        term.write(s.header.as_bytes()).unwrap();
+    }
+}
+
+/// Print the "press space/enter to continue, q to quit" prompt used by `--page`
+/// once a space has filled.
+fn print_page_prompt(term: &mut console::Term) {
+    term.clear_line().unwrap();
+    term.write(b"-- more (space/enter to continue, q to quit) --").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_template_escapes_dollar() {
+        let out = expand_template("cost: $$5", |_| None);
+        assert_eq!(out, Some(String::from("cost: $5")));
+    }
+
+    #[test]
+    fn expand_template_expands_braced_name() {
+        let out = expand_template("user: ${name}!", |key| {
+            if key == "name" {
+                Some(String::from("alice"))
+            } else {
+                None
+            }
+        });
+        assert_eq!(out, Some(String::from("user: alice!")));
+    }
+
+    #[test]
+    fn expand_template_missing_group_falls_back_to_none() {
+        let out = expand_template("id: $1", |_| None);
+        assert_eq!(out, None);
+    }
+
+    #[test]
+    fn render_line_resets_style_on_match_split_by_truncation() {
+        console::set_colors_enabled(true);
+        let pattern = Pattern::compile("fo+", false).unwrap();
+        let out = render_line(&pattern, "foofoofoo", 5);
+
+        // The second "foo" match is split by the 5-column truncation (limit 4),
+        // so its highlighted segment ends early; it must still carry its own
+        // SGR reset rather than leaking color into the rest of the line.
+        let reset = "\u{1b}[0m";
+        assert_eq!(out.matches(reset).count(), 2);
+        assert!(out.ends_with(&format!("{}\n", reset)));
+    }
 }